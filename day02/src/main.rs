@@ -1,4 +1,184 @@
-use std::{env::args, fs::read_to_string, path::Path};
+use std::{
+    env::args,
+    fmt::{self, Display},
+    fs::{read_to_string, File},
+    io::{stdin, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Increasing,
+    Decreasing,
+    Either,
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "increasing" => Ok(Direction::Increasing),
+            "decreasing" => Ok(Direction::Decreasing),
+            "either" => Ok(Direction::Either),
+            _ => Err(format!("Invalid direction: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct SafetyRules {
+    min_step: i32,
+    max_step: i32,
+    allow_plateaus: bool,
+    direction: Direction,
+}
+
+impl Default for SafetyRules {
+    fn default() -> Self {
+        Self {
+            min_step: 1,
+            max_step: 3,
+            allow_plateaus: false,
+            direction: Direction::Either,
+        }
+    }
+}
+
+impl SafetyRules {
+    fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        match key {
+            "min_step" => {
+                self.min_step = value
+                    .parse()
+                    .map_err(|_| format!("Invalid min_step: {}", value))?
+            }
+            "max_step" => {
+                self.max_step = value
+                    .parse()
+                    .map_err(|_| format!("Invalid max_step: {}", value))?
+            }
+            "allow_plateaus" => {
+                self.allow_plateaus = value
+                    .parse()
+                    .map_err(|_| format!("Invalid allow_plateaus: {}", value))?
+            }
+            "direction" => self.direction = value.parse()?,
+            _ => return Err(format!("Unknown safety rule: {}", key)),
+        }
+
+        Ok(())
+    }
+
+    fn directions(&self) -> &'static [bool] {
+        match self.direction {
+            Direction::Increasing => &[true],
+            Direction::Decreasing => &[false],
+            Direction::Either => &[true, false],
+        }
+    }
+
+    fn step_violation(&self, a: i32, b: i32, increasing: bool) -> Option<ViolationKind> {
+        if a == b {
+            return (!self.allow_plateaus).then_some(ViolationKind::Plateau);
+        }
+
+        let distance = if increasing { b - a } else { a - b };
+        if distance < 0 {
+            Some(ViolationKind::WrongDirection)
+        } else if distance < self.min_step {
+            Some(ViolationKind::StepTooSmall)
+        } else if distance > self.max_step {
+            Some(ViolationKind::StepTooLarge)
+        } else {
+            None
+        }
+    }
+
+    fn valid_step(&self, a: i32, b: i32, increasing: bool) -> bool {
+        self.step_violation(a, b, increasing).is_none()
+    }
+}
+
+impl FromStr for SafetyRules {
+    type Err = String;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        let mut rules = SafetyRules::default();
+
+        for line in config.lines() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("Invalid rule line: {}", line))?;
+            rules.set(key.trim(), value.trim())?;
+        }
+
+        Ok(rules)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ViolationKind {
+    Plateau,
+    WrongDirection,
+    StepTooSmall,
+    StepTooLarge,
+}
+
+impl Display for ViolationKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            ViolationKind::Plateau => "plateau",
+            ViolationKind::WrongDirection => "wrong direction",
+            ViolationKind::StepTooSmall => "step too small",
+            ViolationKind::StepTooLarge => "step too large",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Violation {
+    index: usize,
+    kind: ViolationKind,
+}
+
+#[derive(Debug, Clone)]
+struct Diagnosis {
+    safe: bool,
+    violation: Option<Violation>,
+    fix: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Table,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            _ => Err(format!("Invalid output format: {}", s)),
+        }
+    }
+}
+
+fn parse_report(line: &str) -> Vec<i32> {
+    line.split_ascii_whitespace()
+        .map(|level| level.parse().expect("Failed to parse level"))
+        .collect()
+}
 
 fn parse<P>(filename: P) -> Vec<Vec<i32>>
 where
@@ -6,57 +186,376 @@ where
 {
     let raw_input = read_to_string(filename).expect("Failed to read input file");
 
-    raw_input
-        .lines()
-        .map(|line| {
-            line.split_ascii_whitespace()
-                .map(|level| level.parse().expect("Failed to parse level"))
-                .collect()
+    raw_input.lines().map(parse_report).collect()
+}
+
+fn safe_report(report: &[i32], rules: &SafetyRules) -> bool {
+    rules.directions().iter().any(|&increasing| {
+        report
+            .windows(2)
+            .all(|w| rules.valid_step(w[0], w[1], increasing))
+    })
+}
+
+fn safe_report_skipping(
+    report: &[i32],
+    rules: &SafetyRules,
+    skip: usize,
+    increasing: bool,
+) -> bool {
+    let mut levels = report
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != skip)
+        .map(|(_, level)| *level);
+
+    let Some(mut previous) = levels.next() else {
+        return true;
+    };
+
+    levels.all(|level| {
+        let valid = rules.valid_step(previous, level, increasing);
+        previous = level;
+        valid
+    })
+}
+
+fn dampener_removal(report: &[i32], rules: &SafetyRules) -> Option<usize> {
+    rules.directions().iter().find_map(|&increasing| {
+        let i = report
+            .windows(2)
+            .position(|w| !rules.valid_step(w[0], w[1], increasing))?;
+
+        [i, i + 1]
+            .into_iter()
+            .find(|&skip| safe_report_skipping(report, rules, skip, increasing))
+    })
+}
+
+fn dampened_safe_report(report: &[i32], rules: &SafetyRules) -> bool {
+    safe_report(report, rules) || dampener_removal(report, rules).is_some()
+}
+
+fn min_removals_to_safe(report: &[i32], rules: &SafetyRules) -> usize {
+    let longest = rules
+        .directions()
+        .iter()
+        .map(|&increasing| {
+            let mut longest_ending_at = vec![1; report.len()];
+            for j in 0..report.len() {
+                for i in 0..j {
+                    if rules.valid_step(report[i], report[j], increasing) {
+                        longest_ending_at[j] = longest_ending_at[j].max(longest_ending_at[i] + 1);
+                    }
+                }
+            }
+
+            longest_ending_at.into_iter().max().unwrap_or(0)
         })
-        .collect()
+        .max()
+        .unwrap_or(0);
+
+    report.len() - longest
+}
+
+fn first_violation(report: &[i32], rules: &SafetyRules) -> Option<Violation> {
+    let increasing = match rules.direction {
+        Direction::Increasing => true,
+        Direction::Decreasing => false,
+        Direction::Either => report
+            .windows(2)
+            .find(|w| w[0] != w[1])
+            .is_none_or(|w| w[0] < w[1]),
+    };
+
+    report.windows(2).enumerate().find_map(|(index, w)| {
+        rules
+            .step_violation(w[0], w[1], increasing)
+            .map(|kind| Violation { index, kind })
+    })
 }
 
-fn safe_report(report: &[i32]) -> bool {
-    let all_increasing = report.windows(2).all(|w| w[0] <= w[1]);
-    let all_decreasing = report.windows(2).all(|w| w[0] >= w[1]);
-    let distance_valid = report.windows(2).all(|w| {
-        let distance = (w[1] - w[0]).abs();
-        (1..=3).contains(&distance)
-    });
+fn diagnose(report: &[i32], rules: &SafetyRules) -> Diagnosis {
+    if safe_report(report, rules) {
+        return Diagnosis {
+            safe: true,
+            violation: None,
+            fix: None,
+        };
+    }
 
-    (all_increasing || all_decreasing) && distance_valid
+    Diagnosis {
+        safe: false,
+        violation: first_violation(report, rules),
+        fix: dampener_removal(report, rules),
+    }
 }
 
-fn solve_part1(parsed_input: &[Vec<i32>]) -> usize {
+fn print_diagnostics_table(parsed_input: &[Vec<i32>], rules: &SafetyRules) {
+    println!("{:<8}{:<6}{:<40}fix", "report", "safe", "violation");
+    for (i, report) in parsed_input.iter().enumerate() {
+        let diagnosis = diagnose(report, rules);
+        let violation = match diagnosis.violation {
+            Some(violation) => format!(
+                "levels {}-{} ({} -> {}): {}",
+                violation.index,
+                violation.index + 1,
+                report[violation.index],
+                report[violation.index + 1],
+                violation.kind
+            ),
+            None => "-".to_string(),
+        };
+        let fix = match (diagnosis.safe, diagnosis.fix) {
+            (true, _) => "-".to_string(),
+            (false, Some(fix)) => format!("remove level {} ({})", fix, report[fix]),
+            (false, None) => "none".to_string(),
+        };
+        let safe = if diagnosis.safe { "yes" } else { "no" };
+        println!("{:<8}{:<6}{:<40}{}", i, safe, violation, fix);
+    }
+}
+
+fn print_diagnostics_json(parsed_input: &[Vec<i32>], rules: &SafetyRules) {
+    println!("[");
+    for (i, report) in parsed_input.iter().enumerate() {
+        let diagnosis = diagnose(report, rules);
+        let levels = report
+            .iter()
+            .map(|level| level.to_string())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let violation = match diagnosis.violation {
+            Some(violation) => format!(
+                "{{\"index\": {}, \"levels\": [{}, {}], \"kind\": \"{}\"}}",
+                violation.index,
+                report[violation.index],
+                report[violation.index + 1],
+                violation.kind
+            ),
+            None => "null".to_string(),
+        };
+        let fix = match diagnosis.fix {
+            Some(fix) => fix.to_string(),
+            None => "null".to_string(),
+        };
+        let separator = if i + 1 < parsed_input.len() { "," } else { "" };
+        println!(
+            "  {{\"report\": {}, \"levels\": [{}], \"safe\": {}, \"violation\": {}, \"fix\": {}}}{}",
+            i, levels, diagnosis.safe, violation, fix, separator
+        );
+    }
+    println!("]");
+}
+
+fn stream_reports<R>(reader: R, rules: &SafetyRules)
+where
+    R: BufRead,
+{
+    let mut safe_count = 0;
+    let mut dampened_count = 0;
+
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.expect("Failed to read report");
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let report = parse_report(&line);
+        let status = if safe_report(&report, rules) {
+            safe_count += 1;
+            dampened_count += 1;
+            "safe"
+        } else if dampener_removal(&report, rules).is_some() {
+            dampened_count += 1;
+            "dampened"
+        } else {
+            "unsafe"
+        };
+
+        println!(
+            "{}: {} (safe {}, dampened {})",
+            i, status, safe_count, dampened_count
+        );
+    }
+}
+
+fn solve_part1(parsed_input: &[Vec<i32>], rules: &SafetyRules) -> usize {
     parsed_input
         .iter()
-        .filter(|report| safe_report(report))
+        .filter(|report| safe_report(report, rules))
         .count()
 }
 
-fn solve_part2(parsed_input: &[Vec<i32>]) -> usize {
+fn solve_part2(parsed_input: &[Vec<i32>], rules: &SafetyRules) -> usize {
     parsed_input
         .iter()
-        .filter(|report| {
-            for i in 0..report.len() {
-                let mut modified_report = (*report).clone();
-                modified_report.remove(i);
-
-                if safe_report(&modified_report) {
-                    return true;
-                }
-            }
+        .filter(|report| dampened_safe_report(report, rules))
+        .count()
+}
 
-            false
-        })
+fn solve_with_tolerance(parsed_input: &[Vec<i32>], rules: &SafetyRules, tolerance: usize) -> usize {
+    parsed_input
+        .iter()
+        .filter(|report| min_removals_to_safe(report, rules) <= tolerance)
         .count()
 }
 
 fn main() {
-    let filename = args().nth(1).expect("No input filename provided");
+    let mut filename = None;
+    let mut tolerance = None;
+    let mut diagnostics = None;
+    let mut stream = false;
+    let mut rules_filename = None;
+    let mut rule_overrides = Vec::new();
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tolerance" => {
+                let value = args.next().expect("No tolerance provided");
+                tolerance = Some(value.parse().expect("Failed to parse tolerance"));
+            }
+            "--diagnose" => {
+                let value = args.next().expect("No diagnostics format provided");
+                diagnostics = Some(value.parse().expect("Failed to parse diagnostics format"));
+            }
+            "--stream" => stream = true,
+            "--rules" => rules_filename = Some(args.next().expect("No rules filename provided")),
+            "--min-step" => {
+                rule_overrides.push(("min_step", args.next().expect("No min step provided")))
+            }
+            "--max-step" => {
+                rule_overrides.push(("max_step", args.next().expect("No max step provided")))
+            }
+            "--direction" => {
+                rule_overrides.push(("direction", args.next().expect("No direction provided")))
+            }
+            "--allow-plateaus" => rule_overrides.push(("allow_plateaus", "true".to_string())),
+            _ => filename = Some(arg),
+        }
+    }
+
+    let mut rules = match rules_filename {
+        Some(rules_filename) => read_to_string(rules_filename)
+            .expect("Failed to read rules file")
+            .parse()
+            .expect("Invalid rules file"),
+        None => SafetyRules::default(),
+    };
+    for (key, value) in rule_overrides {
+        rules.set(key, &value).expect("Invalid safety rule");
+    }
+
+    if stream {
+        match filename.as_deref() {
+            None | Some("-") => stream_reports(stdin().lock(), &rules),
+            Some(filename) => {
+                let file = File::open(filename).expect("Failed to open input file");
+                stream_reports(BufReader::new(file), &rules);
+            }
+        }
+        return;
+    }
+
+    let filename = filename.expect("No input filename provided");
     let parsed_input = parse(filename);
-    let answer_part1 = solve_part1(&parsed_input);
+    match diagnostics {
+        Some(OutputFormat::Table) => return print_diagnostics_table(&parsed_input, &rules),
+        Some(OutputFormat::Json) => return print_diagnostics_json(&parsed_input, &rules),
+        None => {}
+    }
+
+    let answer_part1 = solve_part1(&parsed_input, &rules);
     println!("{}", answer_part1);
-    let answer_part2 = solve_part2(&parsed_input);
+    let answer_part2 = solve_part2(&parsed_input, &rules);
     println!("{}", answer_part2);
+    if let Some(tolerance) = tolerance {
+        let answer_tolerance = solve_with_tolerance(&parsed_input, &rules, tolerance);
+        println!("{}", answer_tolerance);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn brute_force_dampened(report: &[i32], rules: &SafetyRules) -> bool {
+        (0..report.len()).any(|i| {
+            let mut modified_report = report.to_vec();
+            modified_report.remove(i);
+            safe_report(&modified_report, rules)
+        }) || safe_report(report, rules)
+    }
+
+    fn generate_reports(count: usize) -> Vec<Vec<i32>> {
+        let mut seed = 0x2545f4914f6cdd1d_u64;
+        let mut random = move |bound: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % bound
+        };
+
+        (0..count)
+            .map(|_| {
+                let len = random(9) as usize;
+                (0..len).map(|_| random(10) as i32).collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn dampener_matches_brute_force_on_generated_reports() {
+        let plateaus = SafetyRules {
+            allow_plateaus: true,
+            ..SafetyRules::default()
+        };
+        let increasing = SafetyRules {
+            direction: Direction::Increasing,
+            ..SafetyRules::default()
+        };
+
+        for rules in [SafetyRules::default(), plateaus, increasing] {
+            for report in generate_reports(100_000) {
+                assert_eq!(
+                    dampened_safe_report(&report, &rules),
+                    brute_force_dampened(&report, &rules),
+                    "{:?} with {:?}",
+                    report,
+                    rules
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dampener_matches_brute_force_on_edge_cases() {
+        let rules = SafetyRules::default();
+        let reports: [&[i32]; 12] = [
+            &[],
+            &[5],
+            &[5, 5],
+            &[1, 9],
+            &[1, 2],
+            &[1, 1, 2, 3],
+            &[1, 2, 2, 3],
+            &[1, 2, 3, 3],
+            &[9, 1, 2, 3],
+            &[1, 2, 3, 9],
+            &[4, 1, 2, 3],
+            &[1, 2, 3, 0],
+        ];
+
+        for report in reports {
+            assert_eq!(
+                dampened_safe_report(report, &rules),
+                brute_force_dampened(report, &rules),
+                "{:?}",
+                report
+            );
+        }
+        assert!(reports
+            .iter()
+            .all(|report| dampened_safe_report(report, &rules)));
+    }
 }
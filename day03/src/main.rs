@@ -1,66 +1,596 @@
-use std::{env::args, fs::read_to_string, path::Path};
+use std::{
+    env::args,
+    fs::{read, File},
+    io::{stdin, BufReader, ErrorKind, Read},
+    ops::RangeInclusive,
+    process::exit,
+};
 
-use regex::Regex;
+use memmap2::Mmap;
 
-fn parse<P>(filename: P) -> Vec<String>
+const MAX_OPERANDS: usize = 8;
+const CHUNK_SIZE: usize = 1 << 16;
+// i64::MAX has 19 digits, so no longer operand can ever be scanned.
+const MAX_DIGITS: usize = 19;
+
+#[derive(Debug, Clone)]
+struct Machine {
+    enabled: bool,
+    track_enabled: bool,
+    total: i64,
+    overflowed: usize,
+}
+
+impl Machine {
+    fn new(track_enabled: bool) -> Self {
+        Self {
+            enabled: true,
+            track_enabled,
+            total: 0,
+            overflowed: 0,
+        }
+    }
+
+    fn enable(&mut self) {
+        self.enabled = true;
+    }
+
+    fn disable(&mut self) {
+        if self.track_enabled {
+            self.enabled = false;
+        }
+    }
+
+    fn accumulate(&mut self, value: Option<i64>) {
+        if !self.enabled {
+            return;
+        }
+
+        // An instruction whose result doesn't fit in an i64 is skipped and counted, so
+        // hostile operands can't abort the whole run.
+        match value.and_then(|value| self.total.checked_add(value)) {
+            Some(total) => self.total = total,
+            None => self.overflowed += 1,
+        }
+    }
+}
+
+struct InstructionDefinition {
+    name: String,
+    arity: RangeInclusive<usize>,
+    execute: fn(&mut Machine, &[i64]),
+}
+
+struct InstructionSet {
+    definitions: Vec<InstructionDefinition>,
+    operand_digits: RangeInclusive<usize>,
+}
+
+impl InstructionSet {
+    fn new() -> Self {
+        Self {
+            definitions: Vec::new(),
+            operand_digits: 1..=3,
+        }
+    }
+
+    fn register(
+        &mut self,
+        name: &str,
+        arity: RangeInclusive<usize>,
+        execute: fn(&mut Machine, &[i64]),
+    ) {
+        let definition = InstructionDefinition {
+            name: name.to_string(),
+            arity,
+            execute,
+        };
+
+        match self.definitions.iter_mut().find(|d| d.name == name) {
+            Some(existing) => *existing = definition,
+            None => self.definitions.push(definition),
+        }
+    }
+
+    fn standard() -> Self {
+        let mut set = Self::new();
+        set.register("mul", 2..=2, |machine, operands| {
+            machine.accumulate(operands[0].checked_mul(operands[1]))
+        });
+        set.register("do", 0..=0, |machine, _| machine.enable());
+        set.register("don't", 0..=0, |machine, _| machine.disable());
+
+        set
+    }
+
+    fn extended() -> Self {
+        let mut set = Self::standard();
+        set.register("mul", 2..=MAX_OPERANDS, |machine, operands| {
+            machine.accumulate(
+                operands
+                    .iter()
+                    .try_fold(1i64, |product, &operand| product.checked_mul(operand)),
+            )
+        });
+        set.register("add", 2..=2, |machine, operands| {
+            machine.accumulate(operands[0].checked_add(operands[1]))
+        });
+        set.register("sub", 2..=2, |machine, operands| {
+            machine.accumulate(operands[0].checked_sub(operands[1]))
+        });
+
+        set
+    }
+
+    fn lookahead(&self) -> usize {
+        let longest_name = self.definitions.iter().map(|d| d.name.len()).max();
+        let operands = MAX_OPERANDS * (self.operand_digits.end() + 1);
+
+        longest_name.unwrap_or(0) + 2 + operands
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Instruction {
+    offset: usize,
+    end: usize,
+    opcode: usize,
+    operand_count: usize,
+    operands: [i64; MAX_OPERANDS],
+}
+
+impl Instruction {
+    fn operands(&self) -> &[i64] {
+        &self.operands[..self.operand_count]
+    }
+}
+
+enum Scan {
+    Instruction(Instruction),
+    NearMiss,
+    Nothing,
+}
+
+fn scan_number(
+    memory: &[u8],
+    start: usize,
+    digits: &RangeInclusive<usize>,
+) -> Option<(i64, usize)> {
+    let mut value: i64 = 0;
+    let mut end = start;
+
+    while let Some(digit) = memory.get(end).filter(|c| c.is_ascii_digit()) {
+        value = value.checked_mul(10)?.checked_add((digit - b'0') as i64)?;
+        end += 1;
+    }
+
+    digits.contains(&(end - start)).then_some((value, end))
+}
+
+fn scan_literal(memory: &[u8], start: usize, literal: &[u8]) -> Option<usize> {
+    memory[start..]
+        .starts_with(literal)
+        .then_some(start + literal.len())
+}
+
+fn scan_operands(
+    memory: &[u8],
+    start: usize,
+    digits: &RangeInclusive<usize>,
+) -> Option<([i64; MAX_OPERANDS], usize, usize)> {
+    let mut operands = [0; MAX_OPERANDS];
+
+    if let Some(end) = scan_literal(memory, start, b")") {
+        return Some((operands, 0, end));
+    }
+
+    let mut count = 0;
+    let mut end = start;
+    loop {
+        let (operand, operand_end) = scan_number(memory, end, digits)?;
+        *operands.get_mut(count)? = operand;
+        count += 1;
+
+        if let Some(separator_end) = scan_literal(memory, operand_end, b",") {
+            end = separator_end;
+        } else {
+            let end = scan_literal(memory, operand_end, b")")?;
+            return Some((operands, count, end));
+        }
+    }
+}
+
+fn is_near_miss(memory: &[u8], start: usize) -> bool {
+    let arguments = memory[start..]
+        .iter()
+        .take_while(|&&c| c.is_ascii_digit() || c == b',' || c.is_ascii_whitespace())
+        .count();
+
+    memory.get(start + arguments) == Some(&b')')
+}
+
+fn scan_instruction(memory: &[u8], set: &InstructionSet) -> Scan {
+    let mut near_miss = false;
+
+    for (opcode, definition) in set.definitions.iter().enumerate() {
+        let Some(end) = scan_literal(memory, 0, definition.name.as_bytes())
+            .and_then(|end| scan_literal(memory, end, b"("))
+        else {
+            continue;
+        };
+
+        match scan_operands(memory, end, &set.operand_digits) {
+            Some((operands, operand_count, end)) if definition.arity.contains(&operand_count) => {
+                return Scan::Instruction(Instruction {
+                    offset: 0,
+                    end,
+                    opcode,
+                    operand_count,
+                    operands,
+                });
+            }
+            _ => near_miss |= is_near_miss(memory, end),
+        }
+    }
+
+    if near_miss {
+        Scan::NearMiss
+    } else {
+        Scan::Nothing
+    }
+}
+
+fn scan_chunk(
+    memory: &[u8],
+    base: usize,
+    at_eof: bool,
+    set: &InstructionSet,
+    emit: &mut dyn FnMut(&Instruction),
+) -> (usize, usize) {
+    let lookahead = set.lookahead();
+    let mut rejected = 0;
+    let mut offset = 0;
+
+    while offset < memory.len() && (at_eof || offset + lookahead <= memory.len()) {
+        let window = &memory[offset..memory.len().min(offset + lookahead)];
+        match scan_instruction(window, set) {
+            Scan::Instruction(mut instruction) => {
+                instruction.offset = base + offset;
+                instruction.end += base + offset;
+                offset = instruction.end - base;
+                emit(&instruction);
+            }
+            Scan::NearMiss => {
+                rejected += 1;
+                offset += 1;
+            }
+            Scan::Nothing => offset += 1,
+        }
+    }
+
+    (offset, rejected)
+}
+
+fn scan_stream<R>(mut reader: R, set: &InstructionSet, emit: &mut dyn FnMut(&Instruction)) -> usize
 where
-    P: AsRef<Path>,
+    R: Read,
 {
-    let raw_input = read_to_string(filename).expect("Failed to read input file");
+    let mut buffer = vec![0; CHUNK_SIZE + set.lookahead()];
+    let mut base = 0;
+    let mut len = 0;
+    let mut rejected = 0;
+
+    loop {
+        let read = match reader.read(&mut buffer[len..]) {
+            Ok(read) => read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => panic!("Failed to read input: {}", error),
+        };
+        len += read;
 
-    raw_input.lines().map(|line| line.to_string()).collect()
+        let (consumed, chunk_rejected) = scan_chunk(&buffer[..len], base, read == 0, set, emit);
+        rejected += chunk_rejected;
+        if read == 0 {
+            return rejected;
+        }
+
+        buffer.copy_within(consumed..len, 0);
+        base += consumed;
+        len -= consumed;
+    }
 }
 
-fn execute(parsed_input: &[String], track_enabled: bool) -> i32 {
-    let operations = Regex::new(r"(mul\(\d+,\d+\)|don't\(\)|do\(\))").expect("Invalid regex");
-    let operands = Regex::new(r"mul\((\d+),(\d+)\)").expect("Invalid regex");
+fn tokenize(memory: &[u8], set: &InstructionSet) -> (Vec<Instruction>, usize) {
+    let mut instructions = Vec::new();
+    let (_, rejected) = scan_chunk(memory, 0, true, set, &mut |instruction| {
+        instructions.push(*instruction)
+    });
 
-    let mut enabled = true;
-    parsed_input
-        .iter()
-        .map(|line| {
-            operations
-                .captures_iter(line)
-                .map(|operation| match operation.get(1).unwrap().as_str() {
-                    "don't()" => {
-                        if track_enabled {
-                            enabled = false;
-                        }
-                        0
-                    }
-                    "do()" => {
-                        enabled = true;
-                        0
-                    }
-                    mul => {
-                        if enabled {
-                            let captures = operands.captures(mul).unwrap();
-                            let a = captures.get(1).unwrap().as_str().parse::<i32>().unwrap();
-                            let b = captures.get(2).unwrap().as_str().parse::<i32>().unwrap();
-                            a * b
-                        } else {
-                            0
-                        }
-                    }
-                })
-                .sum::<i32>()
-        })
-        .sum()
+    (instructions, rejected)
+}
+
+fn parse(filename: Option<&str>) -> Vec<u8> {
+    match filename {
+        None | Some("-") => {
+            let mut memory = Vec::new();
+            stdin()
+                .lock()
+                .read_to_end(&mut memory)
+                .expect("Failed to read input");
+            memory
+        }
+        Some(filename) => read(filename).expect("Failed to read input file"),
+    }
 }
 
-fn solve_part1(parsed_input: &[String]) -> i32 {
-    execute(parsed_input, false)
+fn step(machine: &mut Machine, instruction: &Instruction, set: &InstructionSet) {
+    let definition = &set.definitions[instruction.opcode];
+    (definition.execute)(machine, instruction.operands());
+}
+
+fn execute<F>(scan: F, set: &InstructionSet) -> (Machine, Machine, usize)
+where
+    F: FnOnce(&mut dyn FnMut(&Instruction)) -> usize,
+{
+    let mut part1 = Machine::new(false);
+    let mut part2 = Machine::new(true);
+    let rejected = scan(&mut |instruction| {
+        step(&mut part1, instruction, set);
+        step(&mut part2, instruction, set);
+    });
+
+    (part1, part2, rejected)
+}
+
+fn print_trace(instructions: &[Instruction], set: &InstructionSet) {
+    let mut machine = Machine::new(true);
+
+    println!(
+        "{:>10}  {:<24}{:<10}total",
+        "offset", "instruction", "state"
+    );
+    for instruction in instructions {
+        let overflowed = machine.overflowed;
+        let enabled = machine.enabled;
+        step(&mut machine, instruction, set);
+        let state = if machine.overflowed > overflowed {
+            "overflow"
+        } else if enabled {
+            "enabled"
+        } else {
+            "disabled"
+        };
+
+        let operands = instruction
+            .operands()
+            .iter()
+            .map(|operand| operand.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let name = &set.definitions[instruction.opcode].name;
+        println!(
+            "{:>10}  {:<24}{:<10}{}",
+            instruction.offset,
+            format!("{}({})", name, operands),
+            state,
+            machine.total
+        );
+    }
 }
 
-fn solve_part2(parsed_input: &[String]) -> i32 {
-    execute(parsed_input, true)
+fn print_highlighted(memory: &[u8], instructions: &[Instruction], set: &InstructionSet) {
+    let mut machine = Machine::new(true);
+    let mut printed = 0;
+
+    for instruction in instructions {
+        let was_enabled = machine.enabled;
+        step(&mut machine, instruction, set);
+
+        let color = if machine.enabled != was_enabled {
+            "33"
+        } else if was_enabled {
+            "32"
+        } else {
+            "31"
+        };
+        print!(
+            "{}\x1b[1;{}m{}\x1b[0m",
+            String::from_utf8_lossy(&memory[printed..instruction.offset]),
+            color,
+            String::from_utf8_lossy(&memory[instruction.offset..instruction.end])
+        );
+        printed = instruction.end;
+    }
+
+    print!("{}", String::from_utf8_lossy(&memory[printed..]));
 }
 
 fn main() {
-    let filename = args().nth(1).expect("No input filename provided");
-    let parsed_input = parse(filename);
-    let answer_part1 = solve_part1(&parsed_input);
-    println!("{}", answer_part1);
-    let answer_part2 = solve_part2(&parsed_input);
-    println!("{}", answer_part2);
+    let mut filename = None;
+    let mut set = InstructionSet::standard();
+    let mut min_digits = None;
+    let mut max_digits = None;
+    let mut trace = false;
+    let mut highlight = false;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--extended" => set = InstructionSet::extended(),
+            "--trace" => trace = true,
+            "--highlight" => highlight = true,
+            "--min-digits" => {
+                let value = args.next().expect("No minimum digits provided");
+                min_digits = Some(value.parse().expect("Failed to parse minimum digits"));
+            }
+            "--max-digits" => {
+                let value = args.next().expect("No maximum digits provided");
+                max_digits = Some(value.parse().expect("Failed to parse maximum digits"));
+            }
+            _ => filename = Some(arg),
+        }
+    }
+
+    set.operand_digits = min_digits.unwrap_or(*set.operand_digits.start())
+        ..=max_digits.unwrap_or(*set.operand_digits.end());
+    if *set.operand_digits.start() == 0
+        || set.operand_digits.is_empty()
+        || *set.operand_digits.end() > MAX_DIGITS
+    {
+        eprintln!(
+            "Operand digits must satisfy 1 <= min <= max <= {}",
+            MAX_DIGITS
+        );
+        exit(1);
+    }
+
+    if trace || highlight {
+        let memory = parse(filename.as_deref());
+        let (instructions, rejected) = tokenize(&memory, &set);
+        eprintln!("Rejected {} near-miss instructions", rejected);
+        if trace {
+            print_trace(&instructions, &set);
+        }
+        if highlight {
+            print_highlighted(&memory, &instructions, &set);
+        }
+        return;
+    }
+
+    let (part1, part2, rejected) = match filename.as_deref() {
+        None | Some("-") => execute(|emit| scan_stream(stdin().lock(), &set, emit), &set),
+        Some(filename) => {
+            let file = File::open(filename).expect("Failed to open input file");
+            let is_file = file
+                .metadata()
+                .is_ok_and(|metadata| metadata.file_type().is_file());
+            // SAFETY: only regular files are mapped; pipes, FIFOs and devices take the
+            // chunked reader below. The mapping is only read, and only for the duration of
+            // this call, and this program never writes to its input. Another process
+            // truncating the file while we run would be undefined behavior, so inputs that
+            // are still being written should be piped through stdin instead.
+            let memory = is_file.then(|| unsafe { Mmap::map(&file) }.ok()).flatten();
+            match memory {
+                Some(memory) => execute(|emit| scan_chunk(&memory, 0, true, &set, emit).1, &set),
+                None => execute(|emit| scan_stream(BufReader::new(file), &set, emit), &set),
+            }
+        }
+    };
+    eprintln!("Rejected {} near-miss instructions", rejected);
+    for (part, machine) in [(1, &part1), (2, &part2)] {
+        if machine.overflowed > 0 {
+            eprintln!(
+                "Skipped {} overflowing instructions in part {}",
+                machine.overflowed, part
+            );
+        }
+    }
+    println!("{}", part1.total);
+    println!("{}", part2.total);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TinyReads<'a> {
+        memory: &'a [u8],
+        sizes: RangeInclusive<usize>,
+        next: usize,
+    }
+
+    impl Read for TinyReads<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let size = self.next.min(self.memory.len()).min(buf.len());
+            buf[..size].copy_from_slice(&self.memory[..size]);
+            self.memory = &self.memory[size..];
+            self.next = if self.next == *self.sizes.end() {
+                *self.sizes.start()
+            } else {
+                self.next + 1
+            };
+            Ok(size)
+        }
+    }
+
+    fn corrupted_memory() -> Vec<u8> {
+        let pieces: [&[u8]; 8] = [
+            b"mul(12,34)",
+            b"don't()",
+            b"mul(5 ,6)",
+            b"do()",
+            b"mul(123,4)x",
+            b"mul(1,2]",
+            b"add(7,8)mul(9,9,9)",
+            b"\xffmul(4,5)",
+        ];
+
+        let mut seed = 0x2545f4914f6cdd1d_u64;
+        let mut memory = Vec::new();
+        while memory.len() < 3 * CHUNK_SIZE {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            memory.extend_from_slice(pieces[seed as usize % pieces.len()]);
+        }
+
+        memory
+    }
+
+    fn assert_stream_matches_tokenize(
+        memory: &[u8],
+        set: &InstructionSet,
+        sizes: RangeInclusive<usize>,
+    ) {
+        let (instructions, expected_rejected) = tokenize(memory, set);
+        let mut expected = (Machine::new(false), Machine::new(true));
+        for instruction in &instructions {
+            step(&mut expected.0, instruction, set);
+            step(&mut expected.1, instruction, set);
+        }
+
+        let mut offsets = Vec::new();
+        let reader = TinyReads {
+            memory,
+            next: *sizes.start(),
+            sizes,
+        };
+        let (part1, part2, rejected) = execute(
+            |emit| {
+                scan_stream(reader, set, &mut |instruction| {
+                    offsets.push(instruction.offset);
+                    emit(instruction);
+                })
+            },
+            set,
+        );
+
+        assert_eq!(
+            offsets,
+            instructions.iter().map(|i| i.offset).collect::<Vec<_>>()
+        );
+        assert_eq!(rejected, expected_rejected);
+        assert_eq!(part1.total, expected.0.total);
+        assert_eq!(part2.total, expected.1.total);
+    }
+
+    #[test]
+    fn tiny_reads_match_whole_buffer() {
+        let memory = corrupted_memory();
+
+        for set in [InstructionSet::standard(), InstructionSet::extended()] {
+            for sizes in [1..=1, 1..=7, 1..=50] {
+                assert_stream_matches_tokenize(&memory, &set, sizes);
+            }
+        }
+    }
+
+    #[test]
+    fn instructions_straddle_every_read_boundary() {
+        let memory = b"xmul(2,4)%don't()_mul(5,5)+mul(32,64]do()?mul(11,8)";
+        let set = InstructionSet::standard();
+
+        for size in 1..=memory.len() {
+            assert_stream_matches_tokenize(memory, &set, size..=size);
+        }
+    }
 }
@@ -1,4 +1,221 @@
-use std::{env::args, fs::read_to_string, path::Path};
+use std::{
+    collections::HashMap,
+    env::args,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::Path,
+    process::exit,
+    str::FromStr,
+};
+
+const X_MAS: &str = "M.S\n.A.\nM.S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+const DIRECTIONS: [Direction; 8] = [
+    Direction::North,
+    Direction::NorthEast,
+    Direction::East,
+    Direction::SouthEast,
+    Direction::South,
+    Direction::SouthWest,
+    Direction::West,
+    Direction::NorthWest,
+];
+
+impl Direction {
+    fn step(&self) -> (i32, i32) {
+        match self {
+            Direction::North => (0, -1),
+            Direction::NorthEast => (1, -1),
+            Direction::East => (1, 0),
+            Direction::SouthEast => (1, 1),
+            Direction::South => (0, 1),
+            Direction::SouthWest => (-1, 1),
+            Direction::West => (-1, 0),
+            Direction::NorthWest => (-1, -1),
+        }
+    }
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Direction::North => "N",
+            Direction::NorthEast => "NE",
+            Direction::East => "E",
+            Direction::SouthEast => "SE",
+            Direction::South => "S",
+            Direction::SouthWest => "SW",
+            Direction::West => "W",
+            Direction::NorthWest => "NW",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edges {
+    Bounded,
+    /// The grid is a torus. Only rectangular grids may wrap: with ragged rows each row would
+    /// wrap at its own width and bend diagonals, so `main` rejects them.
+    Wrap,
+}
+
+#[derive(Debug, Default)]
+struct TrieNode {
+    children: HashMap<char, usize>,
+    word: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Trie {
+    nodes: Vec<TrieNode>,
+}
+
+impl Trie {
+    fn new(words: &[String]) -> Self {
+        let mut nodes = vec![TrieNode::default()];
+
+        for (i, word) in words.iter().enumerate() {
+            let mut node = 0;
+            for c in word.chars() {
+                node = match nodes[node].children.get(&c) {
+                    Some(&child) => child,
+                    None => {
+                        nodes.push(TrieNode::default());
+                        let child = nodes.len() - 1;
+                        nodes[node].children.insert(c, child);
+                        child
+                    }
+                };
+            }
+            // A repeated dictionary entry is the same word, so only its first copy counts.
+            nodes[node].word.get_or_insert(i);
+        }
+
+        Self { nodes }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct WordMatch {
+    x: usize,
+    y: usize,
+    direction: Direction,
+    word: usize,
+}
+
+impl WordMatch {
+    fn cells(&self, words: &[String]) -> Vec<(i32, i32)> {
+        let (step_x, step_y) = self.direction.step();
+
+        (0..words[self.word].chars().count() as i32)
+            .map(|i| (self.x as i32 + i * step_x, self.y as i32 + i * step_y))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Template {
+    cells: Vec<Vec<Option<char>>>,
+}
+
+impl FromStr for Template {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s
+            .lines()
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>();
+        let width = rows.iter().map(|row| row.chars().count()).max().ok_or(())?;
+        let cells = rows
+            .iter()
+            .map(|row| {
+                let mut cells = row
+                    .chars()
+                    .map(|c| if c == '.' { None } else { Some(c) })
+                    .collect::<Vec<_>>();
+                cells.resize(width, None);
+                cells
+            })
+            .collect();
+
+        Ok(Self { cells })
+    }
+}
+
+impl Template {
+    fn rotate(&self) -> Self {
+        let height = self.cells.len();
+        let width = self.cells[0].len();
+        let cells = (0..width)
+            .map(|x| (0..height).rev().map(|y| self.cells[y][x]).collect())
+            .collect();
+
+        Self { cells }
+    }
+
+    fn reflect(&self) -> Self {
+        let cells = self
+            .cells
+            .iter()
+            .map(|row| row.iter().rev().copied().collect())
+            .collect();
+
+        Self { cells }
+    }
+
+    fn variants(&self) -> Vec<Template> {
+        let mut variants: Vec<Template> = Vec::new();
+        let mut template = self.clone();
+
+        for _ in 0..4 {
+            for variant in [template.clone(), template.reflect()] {
+                if !variants.contains(&variant) {
+                    variants.push(variant);
+                }
+            }
+            template = template.rotate();
+        }
+
+        variants
+    }
+}
+
+#[derive(Debug, Clone)]
+struct TemplateMatch {
+    x: usize,
+    y: usize,
+    variant: usize,
+}
+
+impl TemplateMatch {
+    fn cells(&self, variants: &[Template]) -> Vec<(i32, i32)> {
+        variants[self.variant]
+            .cells
+            .iter()
+            .enumerate()
+            .flat_map(|(dy, row)| {
+                row.iter()
+                    .enumerate()
+                    .filter(|(_, cell)| cell.is_some())
+                    .map(move |(dx, _)| ((self.x + dx) as i32, (self.y + dy) as i32))
+            })
+            .collect()
+    }
+}
 
 fn parse<P>(filename: P) -> Vec<Vec<char>>
 where
@@ -12,80 +229,253 @@ where
         .collect()
 }
 
-fn check_location(
-    parsed_input: &[Vec<char>],
-    needle: &str,
-    x: i32,
-    y: i32,
-    step_x: i32,
-    step_y: i32,
-    offset: i32,
-) -> bool {
-    if needle.chars().enumerate().all(|(i, c)| {
-        let x = x + (i as i32 + offset) * step_x;
-        let y = y + (i as i32 + offset) * step_y;
-        if x < 0 || y < 0 || x >= parsed_input[0].len() as i32 || y >= parsed_input.len() as i32 {
-            return false;
+fn locate(parsed_input: &[Vec<char>], x: i32, y: i32, edges: Edges) -> Option<(usize, usize)> {
+    match edges {
+        Edges::Bounded => {
+            let row = parsed_input.get(usize::try_from(y).ok()?)?;
+            if x < 0 || x >= row.len() as i32 {
+                return None;
+            }
+
+            Some((x as usize, y as usize))
+        }
+        Edges::Wrap => {
+            if parsed_input.is_empty() {
+                return None;
+            }
+
+            let y = y.rem_euclid(parsed_input.len() as i32) as usize;
+            let width = parsed_input[y].len() as i32;
+            if width == 0 {
+                return None;
+            }
+
+            Some((x.rem_euclid(width) as usize, y))
         }
-        parsed_input[y as usize][x as usize] == c
-    }) {
-        return true;
     }
+}
+
+fn get(parsed_input: &[Vec<char>], x: i32, y: i32, edges: Edges) -> Option<char> {
+    let (x, y) = locate(parsed_input, x, y, edges)?;
 
-    false
+    Some(parsed_input[y][x])
 }
 
-fn solve_part1(parsed_input: &[Vec<char>]) -> usize {
-    let mut count = 0;
+fn find_words(parsed_input: &[Vec<char>], words: &[String], edges: Edges) -> Vec<WordMatch> {
+    let trie = Trie::new(words);
+    let mut matches = Vec::new();
 
     for y in 0..parsed_input.len() {
-        for x in 0..parsed_input[0].len() {
-            for step_y in -1..=1 {
-                for step_x in -1..=1 {
-                    if check_location(parsed_input, "XMAS", x as i32, y as i32, step_x, step_y, 0) {
-                        count += 1;
+        for x in 0..parsed_input[y].len() {
+            for direction in DIRECTIONS {
+                let (step_x, step_y) = direction.step();
+                let (mut cursor_x, mut cursor_y) = (x as i32, y as i32);
+                let mut node = 0;
+                let mut used = Vec::new();
+
+                // On a torus the cursor eventually comes back around, but a word may use
+                // each cell only once.
+                while let Some(cell) = locate(parsed_input, cursor_x, cursor_y, edges)
+                    .filter(|cell| !used.contains(cell))
+                {
+                    let Some(&child) = trie.nodes[node].children.get(&parsed_input[cell.1][cell.0])
+                    else {
+                        break;
+                    };
+                    used.push(cell);
+                    node = child;
+
+                    // A one-letter word reads the same in every direction, so it is only
+                    // reported once per cell.
+                    let repeated = used.len() == 1 && direction != DIRECTIONS[0];
+                    if let Some(word) = trie.nodes[node].word.filter(|_| !repeated) {
+                        matches.push(WordMatch {
+                            x,
+                            y,
+                            direction,
+                            word,
+                        });
                     }
+
+                    cursor_x += step_x;
+                    cursor_y += step_y;
                 }
             }
         }
     }
 
-    count
+    matches
 }
 
-fn solve_part2(parsed_input: &[Vec<char>]) -> usize {
-    let mut count = 0;
+fn render(parsed_input: &[Vec<char>], matches: &[Vec<(i32, i32)>], edges: Edges, color: bool) {
+    let mut owners = parsed_input
+        .iter()
+        .map(|row| vec![None; row.len()])
+        .collect::<Vec<_>>();
+    for (i, cells) in matches.iter().enumerate() {
+        for &(x, y) in cells {
+            if let Some((x, y)) = locate(parsed_input, x, y, edges) {
+                owners[y][x] = Some(i);
+            }
+        }
+    }
 
-    for y in 0..parsed_input.len() {
-        for x in 0..parsed_input[0].len() {
-            let mut matches = 0;
+    for (y, row) in parsed_input.iter().enumerate() {
+        let line = row
+            .iter()
+            .enumerate()
+            .map(|(x, c)| match owners[y][x] {
+                Some(i) if color => format!("\x1b[1;{}m{}\x1b[0m", 31 + i % 6, c),
+                Some(_) => c.to_string(),
+                None => ".".to_string(),
+            })
+            .collect::<String>();
+        println!("{}", line);
+    }
+}
 
-            for step_y in -1..=1 {
-                for step_x in -1..=1 {
-                    if step_x == 0 || step_y == 0 {
-                        continue;
-                    }
+fn solve_part1(parsed_input: &[Vec<char>], edges: Edges) -> usize {
+    find_words(parsed_input, &["XMAS".to_string()], edges).len()
+}
 
-                    if check_location(parsed_input, "MAS", x as i32, y as i32, step_x, step_y, -1) {
-                        matches += 1;
-                    }
-                }
-            }
+fn print_word_matches(parsed_input: &[Vec<char>], words: &[String], edges: Edges) {
+    let matches = find_words(parsed_input, words, edges);
+
+    for word_match in &matches {
+        println!(
+            "{} at ({},{}) {}",
+            words[word_match.word], word_match.x, word_match.y, word_match.direction
+        );
+    }
+    println!("{}", matches.len());
+}
 
-            if matches == 2 {
-                count += 1;
+fn find_template(
+    parsed_input: &[Vec<char>],
+    template: &Template,
+    edges: Edges,
+) -> Vec<TemplateMatch> {
+    let variants = template.variants();
+    let mut matches = Vec::new();
+
+    for y in 0..parsed_input.len() {
+        for x in 0..parsed_input[y].len() {
+            for (variant, cells) in variants.iter().enumerate() {
+                let found = cells.cells.iter().enumerate().all(|(dy, row)| {
+                    row.iter().enumerate().all(|(dx, cell)| match cell {
+                        Some(c) => {
+                            get(parsed_input, (x + dx) as i32, (y + dy) as i32, edges) == Some(*c)
+                        }
+                        None => true,
+                    })
+                });
+
+                if found {
+                    matches.push(TemplateMatch { x, y, variant });
+                }
             }
         }
     }
 
-    count
+    matches
+}
+
+fn print_template_matches(parsed_input: &[Vec<char>], template: &Template, edges: Edges) {
+    let matches = find_template(parsed_input, template, edges);
+
+    for template_match in &matches {
+        println!(
+            "({},{}) variant {}",
+            template_match.x, template_match.y, template_match.variant
+        );
+    }
+    println!("{}", matches.len());
+}
+
+fn render_part1(parsed_input: &[Vec<char>], edges: Edges, color: bool) {
+    let words = ["XMAS".to_string()];
+    let matches = find_words(parsed_input, &words, edges)
+        .iter()
+        .map(|word_match| word_match.cells(&words))
+        .collect::<Vec<_>>();
+
+    render(parsed_input, &matches, edges, color);
+}
+
+fn render_part2(parsed_input: &[Vec<char>], edges: Edges, color: bool) {
+    let template: Template = X_MAS.parse().expect("Invalid X-MAS template");
+    let variants = template.variants();
+    let matches = find_template(parsed_input, &template, edges)
+        .iter()
+        .map(|template_match| template_match.cells(&variants))
+        .collect::<Vec<_>>();
+
+    render(parsed_input, &matches, edges, color);
+}
+
+fn solve_part2(parsed_input: &[Vec<char>], edges: Edges) -> usize {
+    let template = X_MAS.parse().expect("Invalid X-MAS template");
+
+    find_template(parsed_input, &template, edges).len()
 }
 
 fn main() {
-    let filename = args().nth(1).expect("No input filename provided");
+    let mut filename = None;
+    let mut words_filename = None;
+    let mut template_filename = None;
+    let mut render_matches = false;
+    let mut color = false;
+    let mut edges = Edges::Bounded;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--words" => words_filename = Some(args.next().expect("No words filename provided")),
+            "--render" => render_matches = true,
+            "--color" => color = true,
+            "--wrap" => edges = Edges::Wrap,
+            "--template" => {
+                template_filename = Some(args.next().expect("No template filename provided"))
+            }
+            _ => filename = Some(arg),
+        }
+    }
+
+    let filename = filename.expect("No input filename provided");
     let parsed_input = parse(filename);
-    let answer_part1 = solve_part1(&parsed_input);
+    if edges == Edges::Wrap
+        && parsed_input
+            .iter()
+            .any(|row| row.len() != parsed_input[0].len())
+    {
+        eprintln!("--wrap requires all rows to have the same width");
+        exit(1);
+    }
+    if let Some(words_filename) = words_filename {
+        let words = read_to_string(words_filename)
+            .expect("Failed to read words file")
+            .lines()
+            .map(|line| line.trim().to_string())
+            .filter(|word| !word.is_empty())
+            .collect::<Vec<_>>();
+        return print_word_matches(&parsed_input, &words, edges);
+    }
+    if let Some(template_filename) = template_filename {
+        let template = read_to_string(template_filename)
+            .expect("Failed to read template file")
+            .parse()
+            .expect("Invalid template");
+        return print_template_matches(&parsed_input, &template, edges);
+    }
+
+    if render_matches {
+        render_part1(&parsed_input, edges, color);
+        println!();
+        render_part2(&parsed_input, edges, color);
+        return;
+    }
+
+    let answer_part1 = solve_part1(&parsed_input, edges);
     println!("{}", answer_part1);
-    let answer_part2 = solve_part2(&parsed_input);
+    let answer_part2 = solve_part2(&parsed_input, edges);
     println!("{}", answer_part2);
 }
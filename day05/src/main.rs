@@ -1,4 +1,78 @@
-use std::{env::args, fs::read_to_string, path::Path};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    env::args,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::Path,
+    time::Instant,
+};
+
+#[derive(Debug, Clone)]
+struct Cycle(Vec<usize>);
+
+impl Display for Cycle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pages = self
+            .0
+            .iter()
+            .chain(self.0.first())
+            .map(|page| page.to_string())
+            .collect::<Vec<_>>();
+        write!(f, "{}", pages.join(" -> "))
+    }
+}
+
+#[derive(Debug, Default)]
+struct Rules {
+    successors: HashMap<usize, HashSet<usize>>,
+}
+
+impl Rules {
+    fn new(orderings: &[(usize, usize)]) -> Self {
+        let mut successors = HashMap::<usize, HashSet<usize>>::new();
+        for &(before, after) in orderings {
+            successors.entry(before).or_default().insert(after);
+        }
+
+        Self { successors }
+    }
+
+    fn requires(&self, before: usize, after: usize) -> bool {
+        self.successors
+            .get(&before)
+            .is_some_and(|successors| successors.contains(&after))
+    }
+
+    fn pages(&self) -> Vec<usize> {
+        self.successors
+            .iter()
+            .flat_map(|(&before, successors)| successors.iter().copied().chain([before]))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Ambiguity {
+    unconstrained: Vec<(usize, usize)>,
+    middle_candidates: Vec<usize>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Move {
+    page: usize,
+    after: Option<usize>,
+}
+
+impl Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.after {
+            Some(after) => write!(f, "move {} after {}", self.page, after),
+            None => write!(f, "move {} to the front", self.page),
+        }
+    }
+}
 
 fn parse<P>(filename: P) -> (Vec<(usize, usize)>, Vec<Vec<usize>>)
 where
@@ -23,63 +97,439 @@ where
     (orderings, updates)
 }
 
-fn is_valid_ordering(update: &[usize], before: usize, after: usize) -> bool {
-    let before_idx = update.iter().position(|&n| n == before);
-    let after_idx = update.iter().position(|&n| n == after);
-    if let (Some(before_idx), Some(after_idx)) = (before_idx, after_idx) {
-        before_idx < after_idx
-    } else {
-        true
+fn is_correctly_ordered(rules: &Rules, update: &[usize]) -> bool {
+    update.iter().enumerate().all(|(i, &before)| {
+        update[i + 1..]
+            .iter()
+            .all(|&after| !rules.requires(after, before))
+    })
+}
+
+fn find_cycle(predecessors: &HashMap<usize, Vec<usize>>, remaining: &HashSet<usize>) -> Cycle {
+    let mut path = Vec::new();
+    let mut page = *remaining.iter().next().unwrap();
+    while !path.contains(&page) {
+        path.push(page);
+        page = *predecessors[&page]
+            .iter()
+            .find(|before| remaining.contains(before))
+            .unwrap();
     }
+
+    let start = path.iter().position(|&p| p == page).unwrap();
+    let mut cycle = path.split_off(start);
+    cycle.reverse();
+    cycle.rotate_right(1);
+
+    Cycle(cycle)
 }
 
-fn is_correctly_ordered(oderings: &[(usize, usize)], update: &[usize]) -> bool {
-    oderings
+fn sort_update(rules: &Rules, update: &[usize]) -> Result<Vec<usize>, Cycle> {
+    let mut in_degree = update
         .iter()
-        .all(|(before, after)| is_valid_ordering(update, *before, *after))
+        .map(|&page| (page, 0))
+        .collect::<HashMap<_, _>>();
+    let mut successors = HashMap::<usize, Vec<usize>>::new();
+    let mut predecessors = HashMap::<usize, Vec<usize>>::new();
+    for &before in update {
+        for &after in update {
+            if rules.requires(before, after) {
+                successors.entry(before).or_default().push(after);
+                predecessors.entry(after).or_default().push(before);
+                *in_degree.get_mut(&after).unwrap() += 1;
+            }
+        }
+    }
+
+    let mut ready = update
+        .iter()
+        .copied()
+        .filter(|page| in_degree[page] == 0)
+        .collect::<VecDeque<_>>();
+    let mut sorted = Vec::with_capacity(update.len());
+    while let Some(page) = ready.pop_front() {
+        sorted.push(page);
+        for after in successors.get(&page).into_iter().flatten() {
+            let degree = in_degree.get_mut(after).unwrap();
+            *degree -= 1;
+            if *degree == 0 {
+                ready.push_back(*after);
+            }
+        }
+    }
+
+    if sorted.len() < in_degree.len() {
+        let remaining = in_degree
+            .into_iter()
+            .filter(|&(_, degree)| degree > 0)
+            .map(|(page, _)| page)
+            .collect();
+        return Err(find_cycle(&predecessors, &remaining));
+    }
+
+    Ok(sorted)
+}
+
+fn validate_rules(rules: &Rules) -> Result<(), Cycle> {
+    sort_update(rules, &rules.pages()).map(|_| ())
+}
+
+fn validate_updates(rules: &Rules, updates: &[Vec<usize>]) -> Vec<(usize, Vec<usize>)> {
+    let mut valid = Vec::new();
+
+    for (i, update) in updates.iter().enumerate() {
+        if let Err(cycle) = sort_update(rules, update) {
+            eprintln!(
+                "Skipping update {}, it cannot be ordered and is left out of both answers: {}",
+                i, cycle
+            );
+            continue;
+        }
+        if find_ambiguity(rules, update)
+            .is_some_and(|ambiguity| ambiguity.middle_candidates.len() > 1)
+        {
+            eprintln!("Warning: update {} has no unique middle page", i);
+        }
+        valid.push((i, update.clone()));
+    }
+
+    valid
+}
+
+fn closure(rules: &Rules, update: &[usize]) -> HashMap<usize, HashSet<usize>> {
+    update
+        .iter()
+        .map(|&page| {
+            let mut reachable = HashSet::new();
+            let mut stack = vec![page];
+            while let Some(before) = stack.pop() {
+                for &after in update {
+                    if rules.requires(before, after) && reachable.insert(after) {
+                        stack.push(after);
+                    }
+                }
+            }
+
+            (page, reachable)
+        })
+        .collect()
 }
 
-fn first_mismatch(oderings: &[(usize, usize)], update: &[usize]) -> Option<(usize, usize)> {
-    oderings
+fn find_ambiguity(rules: &Rules, update: &[usize]) -> Option<Ambiguity> {
+    let descendants = closure(rules, update);
+    let ancestors = |page: usize| {
+        update
+            .iter()
+            .filter(|before| descendants[before].contains(&page))
+            .count()
+    };
+
+    let unconstrained = update
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &a)| update[i + 1..].iter().map(move |&b| (a, b)))
+        .filter(|(a, b)| !descendants[a].contains(b) && !descendants[b].contains(a))
+        .collect::<Vec<_>>();
+    if unconstrained.is_empty() {
+        return None;
+    }
+
+    let middle = update.len() / 2;
+    let middle_candidates = update
         .iter()
-        .find(|(before, after)| !is_valid_ordering(update, *before, *after))
         .copied()
+        .filter(|&page| {
+            let earliest = ancestors(page);
+            let latest = update.len() - 1 - descendants[&page].len();
+            (earliest..=latest).contains(&middle)
+        })
+        .collect();
+
+    Some(Ambiguity {
+        unconstrained,
+        middle_candidates,
+    })
+}
+
+fn print_ambiguities(rules: &Rules, updates: &[(usize, Vec<usize>)]) {
+    for (i, update) in updates {
+        let Some(ambiguity) = find_ambiguity(rules, update) else {
+            continue;
+        };
+
+        let pairs = ambiguity
+            .unconstrained
+            .iter()
+            .map(|(a, b)| format!("{}/{}", a, b))
+            .collect::<Vec<_>>();
+        println!("Update {}: unconstrained pairs {}", i, pairs.join(", "));
+        if ambiguity.middle_candidates.len() > 1 {
+            let candidates = ambiguity
+                .middle_candidates
+                .iter()
+                .map(|page| page.to_string())
+                .collect::<Vec<_>>();
+            println!("  middle page could be any of {}", candidates.join(", "));
+        }
+    }
+}
+
+fn augment(
+    matches: &mut [Option<usize>],
+    edges: &[Vec<usize>],
+    left: usize,
+    visited: &mut [bool],
+) -> bool {
+    for &right in &edges[left] {
+        if visited[right] {
+            continue;
+        }
+        visited[right] = true;
+
+        if matches[right].is_none_or(|other| augment(matches, edges, other, visited)) {
+            matches[right] = Some(left);
+            return true;
+        }
+    }
+
+    false
+}
+
+fn longest_consistent_subsequence(rules: &Rules, update: &[usize]) -> Vec<usize> {
+    let descendants = closure(rules, update);
+    let edges = (0..update.len())
+        .map(|i| {
+            (i + 1..update.len())
+                .filter(|&j| descendants[&update[j]].contains(&update[i]))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut matches = vec![None; update.len()];
+    for left in 0..update.len() {
+        augment(&mut matches, &edges, left, &mut vec![false; update.len()]);
+    }
+
+    let mut matched_left = vec![false; update.len()];
+    for left in matches.iter().flatten() {
+        matched_left[*left] = true;
+    }
+
+    let mut reached_left = vec![false; update.len()];
+    let mut reached_right = vec![false; update.len()];
+    let mut stack = (0..update.len())
+        .filter(|&left| !matched_left[left])
+        .collect::<Vec<_>>();
+    for &left in &stack {
+        reached_left[left] = true;
+    }
+    while let Some(left) = stack.pop() {
+        for &right in &edges[left] {
+            if reached_right[right] {
+                continue;
+            }
+            reached_right[right] = true;
+
+            if let Some(next) = matches[right] {
+                if !reached_left[next] {
+                    reached_left[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+    }
+
+    (0..update.len())
+        .filter(|&i| reached_left[i] && !reached_right[i])
+        .map(|i| update[i])
+        .collect()
+}
+
+fn plan_corrections(rules: &Rules, update: &[usize]) -> (Vec<Move>, Vec<usize>) {
+    let kept = longest_consistent_subsequence(rules, update);
+    let constraints = update
+        .iter()
+        .flat_map(|&before| update.iter().map(move |&after| (before, after)))
+        .filter(|&(before, after)| rules.requires(before, after))
+        .chain(kept.windows(2).map(|w| (w[0], w[1])))
+        .collect::<Vec<_>>();
+    let target = sort_update(&Rules::new(&constraints), update).expect("Update cannot be ordered");
+
+    let mut current = update.to_vec();
+    let mut moves = Vec::new();
+    for (i, &page) in target.iter().enumerate() {
+        if kept.contains(&page) {
+            continue;
+        }
+
+        let after = i.checked_sub(1).map(|j| target[j]);
+        current.retain(|&p| p != page);
+        let position = match after {
+            Some(after) => current.iter().position(|&p| p == after).unwrap() + 1,
+            None => 0,
+        };
+        current.insert(position, page);
+        moves.push(Move { page, after });
+    }
+
+    (moves, current)
+}
+
+fn print_corrections(rules: &Rules, updates: &[(usize, Vec<usize>)]) {
+    for (i, update) in updates {
+        if is_correctly_ordered(rules, update) {
+            continue;
+        }
+
+        let (moves, corrected) = plan_corrections(rules, update);
+        println!("Update {}: {} moves", i, moves.len());
+        for page_move in &moves {
+            println!("  {}", page_move);
+        }
+        let corrected = corrected
+            .iter()
+            .map(|page| page.to_string())
+            .collect::<Vec<_>>();
+        println!("  result: {}", corrected.join(","));
+    }
 }
 
 fn middle_page_number(update: &[usize]) -> usize {
     update[update.len() / 2]
 }
 
-fn solve_part1(oderings: &[(usize, usize)], updates: &[Vec<usize>]) -> usize {
+fn solve_part1(rules: &Rules, updates: &[Vec<usize>]) -> usize {
     updates
         .iter()
-        .filter(|&update| is_correctly_ordered(oderings, update))
+        .filter(|&update| is_correctly_ordered(rules, update))
         .map(|update| middle_page_number(update))
         .sum()
 }
 
-fn solve_part2(oderings: &[(usize, usize)], updates: Vec<Vec<usize>>) -> usize {
+fn solve_part2(rules: &Rules, updates: &[Vec<usize>]) -> usize {
     updates
-        .into_iter()
-        .filter(|update| !is_correctly_ordered(oderings, update))
-        .map(|mut update| {
-            while let Some((before, after)) = first_mismatch(oderings, &update) {
-                let before_idx = update.iter().position(|&n| n == before).unwrap();
-                let after_idx = update.iter().position(|&n| n == after).unwrap();
-                update.swap(before_idx, after_idx);
-            }
+        .iter()
+        .filter(|update| !is_correctly_ordered(rules, update))
+        .filter_map(|update| sort_update(rules, update).ok())
+        .map(|update| middle_page_number(&update))
+        .sum()
+}
+
+// The longest update in the real puzzle input has 23 pages.
+const BENCHMARK_UPDATE_LENGTH: usize = 23;
+
+fn generate_benchmark(pages: usize, updates: usize) -> (Vec<(usize, usize)>, Vec<Vec<usize>>) {
+    let mut seed = 0x2545f4914f6cdd1d_u64;
+    let mut random = move |bound: usize| {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed as usize % bound
+    };
+
+    let mut order = (0..pages).collect::<Vec<_>>();
+    for i in (1..pages).rev() {
+        order.swap(i, random(i + 1));
+    }
 
+    let orderings = (0..pages)
+        .flat_map(|i| (i + 1..pages).map(move |j| (i, j)))
+        .map(|(i, j)| (order[i], order[j]))
+        .collect();
+    let updates = (0..updates)
+        .map(|_| {
+            let mut update = (0..pages).collect::<Vec<_>>();
+            for i in (1..pages).rev() {
+                update.swap(i, random(i + 1));
+            }
+            update.truncate(BENCHMARK_UPDATE_LENGTH.min(pages));
             update
         })
-        .map(|update| middle_page_number(&update))
-        .sum()
+        .collect();
+
+    (orderings, updates)
+}
+
+fn run_benchmark(pages: usize, updates: usize) {
+    let (orderings, updates) = generate_benchmark(pages, updates);
+    println!("{} rules, {} updates", orderings.len(), updates.len());
+
+    let start = Instant::now();
+    let rules = Rules::new(&orderings);
+    println!("index: {:?}", start.elapsed());
+
+    let start = Instant::now();
+    let updates = validate_updates(&rules, &updates)
+        .into_iter()
+        .map(|(_, update)| update)
+        .collect::<Vec<_>>();
+    println!(
+        "validate: {} orderable in {:?}",
+        updates.len(),
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    let answer_part1 = solve_part1(&rules, &updates);
+    println!("part 1: {} in {:?}", answer_part1, start.elapsed());
+
+    let start = Instant::now();
+    let answer_part2 = solve_part2(&rules, &updates);
+    println!("part 2: {} in {:?}", answer_part2, start.elapsed());
 }
 
 fn main() {
-    let filename = args().nth(1).expect("No input filename provided");
+    let mut filename = None;
+    let mut ambiguity = false;
+    let mut corrections = false;
+    let mut check_rules = false;
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--benchmark" => {
+                let pages = args
+                    .next()
+                    .expect("No page count provided")
+                    .parse()
+                    .expect("Failed to parse page count");
+                let updates = args
+                    .next()
+                    .expect("No update count provided")
+                    .parse()
+                    .expect("Failed to parse update count");
+                return run_benchmark(pages, updates);
+            }
+            "--ambiguity" => ambiguity = true,
+            "--corrections" => corrections = true,
+            "--check-rules" => check_rules = true,
+            _ => filename = Some(arg),
+        }
+    }
+
+    let filename = filename.expect("No input filename provided");
     let (orderings, updates) = parse(filename);
-    let answer_part1 = solve_part1(&orderings, &updates);
+    let rules = Rules::new(&orderings);
+    // Real inputs order every pair of pages, which is cyclic as a whole, so only updates
+    // are checked by default.
+    if check_rules {
+        if let Err(cycle) = validate_rules(&rules) {
+            eprintln!("Warning: ordering rules contain a cycle: {}", cycle);
+        }
+    }
+    let updates = validate_updates(&rules, &updates);
+    if ambiguity {
+        return print_ambiguities(&rules, &updates);
+    }
+    if corrections {
+        return print_corrections(&rules, &updates);
+    }
+
+    let updates = updates
+        .into_iter()
+        .map(|(_, update)| update)
+        .collect::<Vec<_>>();
+    let answer_part1 = solve_part1(&rules, &updates);
     println!("{}", answer_part1);
-    let answer_part2 = solve_part2(&orderings, updates);
+    let answer_part2 = solve_part2(&rules, &updates);
     println!("{}", answer_part2);
 }
@@ -1,4 +1,13 @@
-use std::{collections::HashSet, env::args, fs::read_to_string, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    env::args,
+    fmt::{self, Display},
+    fs::read_to_string,
+    path::Path,
+    process::exit,
+};
+
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 
 #[derive(Debug, Clone)]
 enum Tile {
@@ -14,6 +23,51 @@ enum Direction {
     Right,
 }
 
+const DIRECTIONS: [Direction; 4] = [
+    Direction::Up,
+    Direction::Right,
+    Direction::Down,
+    Direction::Left,
+];
+
+impl Direction {
+    fn step(&self) -> (i32, i32) {
+        match self {
+            Direction::Left => (-1, 0),
+            Direction::Right => (1, 0),
+            Direction::Up => (0, -1),
+            Direction::Down => (0, 1),
+        }
+    }
+
+    fn turn_right(&self) -> Direction {
+        match self {
+            Direction::Left => Direction::Up,
+            Direction::Right => Direction::Down,
+            Direction::Up => Direction::Right,
+            Direction::Down => Direction::Left,
+        }
+    }
+
+    fn symbol(&self) -> char {
+        match self {
+            Direction::Up => '^',
+            Direction::Right => '>',
+            Direction::Down => 'v',
+            Direction::Left => '<',
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Direction::Up => 0,
+            Direction::Right => 1,
+            Direction::Down => 2,
+            Direction::Left => 3,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Guard {
     x: i32,
@@ -21,126 +75,524 @@ struct Guard {
     direction: Direction,
 }
 
-fn parse<P>(filename: P) -> (Vec<Vec<Tile>>, Guard)
+#[derive(Debug, Clone)]
+enum ParseError {
+    NoGuard,
+    EmptyRow,
+    InvalidCharacter {
+        c: char,
+        x: usize,
+        y: usize,
+    },
+    RaggedRow {
+        y: usize,
+        width: usize,
+        expected: usize,
+    },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::NoGuard => write!(f, "no guard found"),
+            ParseError::EmptyRow => write!(f, "first row is empty"),
+            ParseError::InvalidCharacter { c, x, y } => {
+                write!(f, "invalid character {:?} at ({},{})", c, x, y)
+            }
+            ParseError::RaggedRow { y, width, expected } => {
+                write!(f, "row {} has width {}, expected {}", y, width, expected)
+            }
+        }
+    }
+}
+
+fn parse<P>(filename: P) -> Result<(Vec<Vec<Tile>>, Vec<Guard>), ParseError>
 where
     P: AsRef<Path>,
 {
     let raw_input = read_to_string(filename).expect("Failed to read input file");
 
-    let mut guard = None;
-    let grid = raw_input
-        .lines()
-        .enumerate()
-        .map(|(y, line)| {
-            line.chars()
-                .enumerate()
-                .map(|(x, c)| match c {
-                    '.' => Tile::Empty,
-                    '#' => Tile::Obstacle,
-                    '^' => {
-                        guard = Some(Guard {
-                            x: x as i32,
-                            y: y as i32,
-                            direction: Direction::Up,
-                        });
-                        Tile::Empty
-                    }
-                    _ => panic!("Invalid character in input"),
-                })
-                .collect()
-        })
-        .collect();
+    let mut guards = Vec::new();
+    let mut grid = Vec::new();
+    for (y, line) in raw_input.lines().enumerate() {
+        let mut row = Vec::new();
+        for (x, c) in line.chars().enumerate() {
+            let direction = match c {
+                '.' => {
+                    row.push(Tile::Empty);
+                    continue;
+                }
+                '#' => {
+                    row.push(Tile::Obstacle);
+                    continue;
+                }
+                '^' => Direction::Up,
+                '>' => Direction::Right,
+                'v' => Direction::Down,
+                '<' => Direction::Left,
+                _ => return Err(ParseError::InvalidCharacter { c, x, y }),
+            };
+
+            guards.push(Guard {
+                x: x as i32,
+                y: y as i32,
+                direction,
+            });
+            row.push(Tile::Empty);
+        }
+
+        let expected = grid.first().map_or(row.len(), Vec::len);
+        if expected == 0 {
+            return Err(ParseError::EmptyRow);
+        }
+        if row.len() != expected {
+            return Err(ParseError::RaggedRow {
+                y,
+                width: row.len(),
+                expected,
+            });
+        }
+        grid.push(row);
+    }
+
+    if guards.is_empty() {
+        return Err(ParseError::NoGuard);
+    }
 
-    (grid, guard.unwrap())
+    Ok((grid, guards))
 }
 
 fn on_grid(grid: &[Vec<Tile>], x: i32, y: i32) -> bool {
     x >= 0 && x < grid[0].len() as i32 && y >= 0 && y < grid.len() as i32
 }
 
-fn run_guard(grid: &[Vec<Tile>], guard: &mut Guard) -> HashSet<Guard> {
-    let mut history = HashSet::new();
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Advance {
+    Moved,
+    Turned,
+    LeftMap,
+}
+
+fn advance<F>(grid: &[Vec<Tile>], guard: &mut Guard, blocked: F) -> Advance
+where
+    F: Fn((i32, i32)) -> bool,
+{
+    let (step_x, step_y) = guard.direction.step();
+    let (x, y) = (guard.x + step_x, guard.y + step_y);
+    if !on_grid(grid, x, y) {
+        return Advance::LeftMap;
+    }
+
+    if matches!(grid[y as usize][x as usize], Tile::Obstacle) || blocked((x, y)) {
+        guard.direction = guard.direction.turn_right();
+        Advance::Turned
+    } else {
+        guard.x = x;
+        guard.y = y;
+        Advance::Moved
+    }
+}
 
-    loop {
-        if history.contains(guard) {
-            break;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Termination {
+    LeftMap { steps: usize },
+    Loop { step: usize, length: usize },
+}
+
+impl Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Termination::LeftMap { steps } => write!(f, "left the map after {} steps", steps),
+            Termination::Loop { step, length } => {
+                write!(f, "entered a loop of length {} at step {}", length, step)
+            }
         }
+    }
+}
 
-        history.insert(guard.clone());
+struct Patrol<'a> {
+    grid: &'a [Vec<Tile>],
+    guard: Guard,
+    history: HashMap<Guard, usize>,
+    step: usize,
+    termination: Option<Termination>,
+}
 
-        let (x, y) = match guard.direction {
-            Direction::Left => (guard.x - 1, guard.y),
-            Direction::Right => (guard.x + 1, guard.y),
-            Direction::Up => (guard.x, guard.y - 1),
-            Direction::Down => (guard.x, guard.y + 1),
-        };
+impl<'a> Patrol<'a> {
+    fn new(grid: &'a [Vec<Tile>], guard: &Guard) -> Self {
+        Self {
+            grid,
+            guard: guard.clone(),
+            history: HashMap::new(),
+            step: 0,
+            termination: None,
+        }
+    }
+
+    fn termination(&self) -> Option<Termination> {
+        self.termination
+    }
+}
+
+impl Iterator for Patrol<'_> {
+    type Item = Guard;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.termination.is_some() {
+            return None;
+        }
+
+        if let Some(&step) = self.history.get(&self.guard) {
+            let length = self.step - step;
+            self.termination = Some(Termination::Loop { step, length });
+            return None;
+        }
+        self.history.insert(self.guard.clone(), self.step);
 
-        if !on_grid(grid, x, y) {
-            guard.x = x;
-            guard.y = y;
+        let current = self.guard.clone();
+        self.step += 1;
 
-            break;
+        if advance(self.grid, &mut self.guard, |_| false) == Advance::LeftMap {
+            self.termination = Some(Termination::LeftMap { steps: self.step });
         }
 
-        match grid[y as usize][x as usize] {
-            Tile::Obstacle => match guard.direction {
-                Direction::Left => guard.direction = Direction::Up,
-                Direction::Right => guard.direction = Direction::Down,
-                Direction::Up => guard.direction = Direction::Right,
-                Direction::Down => guard.direction = Direction::Left,
-            },
-            _ => {
-                guard.x = x;
-                guard.y = y;
+        Some(current)
+    }
+}
+
+struct JumpTable {
+    width: usize,
+    stops: Vec<[Option<(i32, i32)>; 4]>,
+}
+
+impl JumpTable {
+    fn new(grid: &[Vec<Tile>]) -> Self {
+        let width = grid[0].len();
+        let height = grid.len();
+        let mut stops = vec![[None; 4]; width * height];
+
+        for direction in DIRECTIONS {
+            let (step_x, step_y) = direction.step();
+            let xs = if step_x > 0 {
+                (0..width).rev().collect::<Vec<_>>()
+            } else {
+                (0..width).collect()
+            };
+            let ys = if step_y > 0 {
+                (0..height).rev().collect::<Vec<_>>()
+            } else {
+                (0..height).collect()
+            };
+
+            for &y in &ys {
+                for &x in &xs {
+                    let (next_x, next_y) = (x as i32 + step_x, y as i32 + step_y);
+                    stops[y * width + x][direction.index()] = if !on_grid(grid, next_x, next_y) {
+                        None
+                    } else if matches!(grid[next_y as usize][next_x as usize], Tile::Obstacle) {
+                        Some((x as i32, y as i32))
+                    } else {
+                        stops[next_y as usize * width + next_x as usize][direction.index()]
+                    };
+                }
             }
         }
+
+        Self { width, stops }
     }
 
-    history
+    fn stop(&self, guard: &Guard, obstacle: (i32, i32)) -> Option<(i32, i32)> {
+        let stop =
+            self.stops[guard.y as usize * self.width + guard.x as usize][guard.direction.index()];
+
+        let (step_x, step_y) = guard.direction.step();
+        let distance = match guard.direction {
+            Direction::Up | Direction::Down => (obstacle.1 - guard.y) * step_y,
+            Direction::Left | Direction::Right => (obstacle.0 - guard.x) * step_x,
+        };
+        let in_line = match guard.direction {
+            Direction::Up | Direction::Down => obstacle.0 == guard.x,
+            Direction::Left | Direction::Right => obstacle.1 == guard.y,
+        };
+        let blocked = in_line
+            && distance > 0
+            && stop.is_none_or(|(x, y)| distance <= (x - guard.x).abs() + (y - guard.y).abs());
+
+        if blocked {
+            Some((obstacle.0 - step_x, obstacle.1 - step_y))
+        } else {
+            stop
+        }
+    }
 }
 
-fn solve_part1(grid: &[Vec<Tile>], guard: &Guard) -> usize {
-    let mut guard = (*guard).clone();
+fn traps_guard(table: &JumpTable, guard: &Guard, obstacle: (i32, i32)) -> bool {
+    let mut turns = vec![false; table.stops.len() * 4];
+    let mut guard = guard.clone();
 
-    let history = run_guard(grid, &mut guard);
+    while let Some((x, y)) = table.stop(&guard, obstacle) {
+        guard.x = x;
+        guard.y = y;
+        guard.direction = guard.direction.turn_right();
 
-    history
-        .iter()
+        let turn = (y as usize * table.width + x as usize) * 4 + guard.direction.index();
+        if turns[turn] {
+            return true;
+        }
+        turns[turn] = true;
+    }
+
+    false
+}
+
+fn solve_part1(grid: &[Vec<Tile>], guard: &Guard) -> usize {
+    Patrol::new(grid, guard)
         .map(|guard| (guard.x, guard.y))
         .collect::<HashSet<_>>()
         .len()
 }
 
-fn solve_part2(grid: &Vec<Vec<Tile>>, original_guard: &Guard) -> usize {
-    let mut grid = (*grid).clone();
-    let mut count = 0;
+fn first_entries(grid: &[Vec<Tile>], guard: &Guard) -> Vec<((i32, i32), Guard)> {
+    let mut visited = HashSet::from([(guard.x, guard.y)]);
+
+    Patrol::new(grid, guard)
+        .collect::<Vec<_>>()
+        .windows(2)
+        .filter(|w| visited.insert((w[1].x, w[1].y)))
+        .map(|w| ((w[1].x, w[1].y), w[0].clone()))
+        .collect()
+}
+
+fn find_obstructions(grid: &[Vec<Tile>], guard: &Guard) -> Vec<(i32, i32)> {
+    let table = JumpTable::new(grid);
 
-    for y in 0..grid.len() {
-        for x in 0..grid[0].len() {
-            if matches!(grid[y][x], Tile::Obstacle) {
+    first_entries(grid, guard)
+        .par_iter()
+        .filter(|(obstacle, guard)| traps_guard(&table, guard, *obstacle))
+        .map(|(obstacle, _)| *obstacle)
+        .collect()
+}
+
+fn run_lockstep(
+    grid: &[Vec<Tile>],
+    guards: &[Guard],
+    obstacle: Option<(i32, i32)>,
+) -> (Vec<Guard>, Vec<bool>) {
+    let mut active = guards.iter().cloned().map(Some).collect::<Vec<_>>();
+    let mut states = Vec::new();
+    let mut history = HashSet::new();
+
+    while active.iter().any(Option::is_some) {
+        if !history.insert(active.clone()) {
+            let trapped = active.iter().map(Option::is_some).collect();
+            return (states, trapped);
+        }
+        states.extend(active.iter().flatten().cloned());
+
+        // Guards move one at a time in reading order, so the earlier guard wins a contested
+        // cell, and a cell vacated earlier in the tick can be entered by a later guard.
+        let mut occupied = active
+            .iter()
+            .flatten()
+            .map(|guard| (guard.x, guard.y))
+            .collect::<HashSet<_>>();
+        for slot in active.iter_mut() {
+            let Some(guard) = slot else {
                 continue;
+            };
+
+            let start = (guard.x, guard.y);
+            let blocked = |position| obstacle == Some(position) || occupied.contains(&position);
+            match advance(grid, guard, blocked) {
+                Advance::Moved => {
+                    occupied.remove(&start);
+                    occupied.insert((guard.x, guard.y));
+                }
+                Advance::Turned => {}
+                Advance::LeftMap => {
+                    occupied.remove(&start);
+                    *slot = None;
+                }
             }
+        }
+    }
 
-            let mut guard = (*original_guard).clone();
+    (states, vec![false; guards.len()])
+}
 
-            grid[y][x] = Tile::Obstacle;
-            run_guard(&grid, &mut guard);
-            grid[y][x] = Tile::Empty;
+fn trajectories(grid: &[Vec<Tile>], guards: &[Guard], blocking: bool) -> Vec<Guard> {
+    if blocking {
+        return run_lockstep(grid, guards, None).0;
+    }
 
-            if on_grid(&grid, guard.x, guard.y) {
-                count += 1;
-            }
+    guards
+        .iter()
+        .flat_map(|guard| Patrol::new(grid, guard))
+        .collect()
+}
+
+fn combined_coverage(grid: &[Vec<Tile>], guards: &[Guard], blocking: bool) -> HashSet<(i32, i32)> {
+    trajectories(grid, guards, blocking)
+        .iter()
+        .map(|guard| (guard.x, guard.y))
+        .collect()
+}
+
+fn trapped_guards(
+    grid: &[Vec<Tile>],
+    guards: &[Guard],
+    blocking: bool,
+) -> Vec<((i32, i32), Vec<bool>)> {
+    let table = JumpTable::new(grid);
+    let starts = guards
+        .iter()
+        .map(|guard| (guard.x, guard.y))
+        .collect::<HashSet<_>>();
+    let candidates = combined_coverage(grid, guards, blocking)
+        .difference(&starts)
+        .copied()
+        .collect::<Vec<_>>();
+
+    candidates
+        .par_iter()
+        .map(|&obstacle| {
+            let trapped = if blocking {
+                run_lockstep(grid, guards, Some(obstacle)).1
+            } else {
+                guards
+                    .iter()
+                    .map(|guard| traps_guard(&table, guard, obstacle))
+                    .collect()
+            };
+            (obstacle, trapped)
+        })
+        .collect()
+}
+
+fn render(grid: &[Vec<Tile>], guards: &[Guard], blocking: bool, obstructions: &[(i32, i32)]) {
+    let mut axes = HashMap::<(i32, i32), (bool, bool)>::new();
+    for guard in trajectories(grid, guards, blocking) {
+        let axis = axes.entry((guard.x, guard.y)).or_default();
+        if matches!(guard.direction, Direction::Up | Direction::Down) {
+            axis.0 = true;
+        } else {
+            axis.1 = true;
         }
     }
 
-    count
+    for (y, row) in grid.iter().enumerate() {
+        let line = row
+            .iter()
+            .enumerate()
+            .map(|(x, tile)| {
+                let position = (x as i32, y as i32);
+                if matches!(tile, Tile::Obstacle) {
+                    '#'
+                } else if obstructions.contains(&position) {
+                    'O'
+                } else if let Some(start) = guards.iter().find(|g| (g.x, g.y) == position) {
+                    start.direction.symbol()
+                } else {
+                    match axes.get(&position) {
+                        Some((true, true)) => '+',
+                        Some((true, false)) => '|',
+                        Some((false, true)) => '-',
+                        _ => '.',
+                    }
+                }
+            })
+            .collect::<String>();
+        println!("{}", line);
+    }
+}
+
+fn print_patrol(grid: &[Vec<Tile>], guard: &Guard) {
+    let mut patrol = Patrol::new(grid, guard);
+
+    for (step, guard) in patrol.by_ref().enumerate() {
+        println!(
+            "{}: ({},{}) {}",
+            step,
+            guard.x,
+            guard.y,
+            guard.direction.symbol()
+        );
+    }
+    if let Some(termination) = patrol.termination() {
+        println!("{}", termination);
+    }
+}
+
+fn solve_part2(grid: &[Vec<Tile>], guard: &Guard) -> usize {
+    find_obstructions(grid, guard).len()
+}
+
+fn solve_guards(grid: &[Vec<Tile>], guards: &[Guard], blocking: bool) -> (usize, usize, usize) {
+    let coverage = combined_coverage(grid, guards, blocking).len();
+    let trapped = trapped_guards(grid, guards, blocking);
+    let trapping_any = trapped
+        .iter()
+        .filter(|(_, trapped)| trapped.iter().any(|&t| t))
+        .count();
+    let trapping_all = trapped
+        .iter()
+        .filter(|(_, trapped)| trapped.iter().all(|&t| t))
+        .count();
+
+    (coverage, trapping_any, trapping_all)
 }
 
 fn main() {
-    let filename = args().nth(1).expect("No input filename provided");
-    let (grid, guard) = parse(filename);
-    let answer_part1 = solve_part1(&grid, &guard);
-    println!("{}", answer_part1);
-    let answer_part2 = solve_part2(&grid, &guard);
-    println!("{}", answer_part2);
+    let mut filename = None;
+    let mut render_map = false;
+    let mut patrol = false;
+    let mut blocking = false;
+    for arg in args().skip(1) {
+        match arg.as_str() {
+            "--render" => render_map = true,
+            "--patrol" => patrol = true,
+            "--blocking" => blocking = true,
+            _ => filename = Some(arg),
+        }
+    }
+
+    let filename = filename.expect("No input filename provided");
+    let (grid, guards) = match parse(filename) {
+        Ok(parsed_input) => parsed_input,
+        Err(error) => {
+            eprintln!("Invalid lab map: {}", error);
+            exit(1);
+        }
+    };
+    if patrol {
+        for (i, guard) in guards.iter().enumerate() {
+            println!("Guard {}", i);
+            print_patrol(&grid, guard);
+        }
+        return;
+    }
+    if render_map {
+        let obstructions = match guards.as_slice() {
+            [guard] => find_obstructions(&grid, guard),
+            _ => trapped_guards(&grid, &guards, blocking)
+                .into_iter()
+                .filter(|(_, trapped)| trapped.iter().any(|&t| t))
+                .map(|(obstacle, _)| obstacle)
+                .collect(),
+        };
+        return render(&grid, &guards, blocking, &obstructions);
+    }
+
+    if let ([guard], false) = (guards.as_slice(), blocking) {
+        let answer_part1 = solve_part1(&grid, guard);
+        println!("{}", answer_part1);
+        let answer_part2 = solve_part2(&grid, guard);
+        println!("{}", answer_part2);
+        return;
+    }
+
+    let (coverage, trapping_any, trapping_all) = solve_guards(&grid, &guards, blocking);
+    println!("{}", coverage);
+    println!("{}", trapping_any);
+    println!("{}", trapping_all);
 }